@@ -3,10 +3,11 @@ extern crate walkdir;
 extern crate serde_derive;
 extern crate serde_yaml;
 extern crate genepa_rs;
+extern crate noodles;
 
 use std::fmt::Display;
 use std::process::{Command, Stdio};
-use std::io::{BufReader, BufRead, Read};
+use std::io::{BufReader, BufRead, Read, Seek};
 use std::fs::File;
 use std::result::Result;
 use std::iter::{Iterator, FromIterator};
@@ -127,15 +128,14 @@ impl Component {
             Err(e) => { return Err(ComponentQueryError::TabixError(e)); }
         };
 
-        // Convert the tabix results to a vector and filter irrelevant entries.
-        let mut vec: Vec<AssociationStat> = tabix
+        // Convert the tabix results to a vector and keep entries matching the
+        // variant, allowing a strand flip when direct matching fails.
+        let mut vec: Vec<(AssociationStat, bool)> = tabix
             .into_iter()
             .filter_map(|result| {
-                // Keep only statistics matching the variant.
                 match result {
-                    Ok(stat) => {
-                        if &stat.variant == v { Some(stat) } else { None }
-                    },
+                    Ok(stat) => harmonize_variant(v, &stat.variant)
+                        .map(|flipped| (stat, flipped)),
                     Err(_) => None
                 }
             })
@@ -148,7 +148,19 @@ impl Component {
         }
 
         else if vec.len() == 1 {
-            let mut stat = vec.pop().unwrap();
+            let (mut stat, flipped) = vec.pop().unwrap();
+
+            // Re-express the stored alleles on the query strand so that the
+            // coded-allele handling below operates in a single frame.
+            if flipped {
+                stat.variant.alleles = (
+                    reverse_complement(&stat.variant.alleles.0)
+                        .unwrap_or_else(|| stat.variant.alleles.0.clone()),
+                    reverse_complement(&stat.variant.alleles.1)
+                        .unwrap_or_else(|| stat.variant.alleles.1.clone()),
+                );
+            }
+            stat.strand_flipped = flipped;
 
             // Express the stats according to coded_allele.
             let current_coded_allele = match stat.coded_allele {
@@ -175,7 +187,51 @@ impl Component {
     pub fn get_stats_for_region(&self, region: &str)
         -> Result<SummaryStatsFile, String>
     {
-        SummaryStatsFile::tabix(&self.formatted_file, region)
+        // Use an indexed seek when a .tbi/.csi sidecar is available next to
+        // the statistics file, otherwise fall back to a full linear scan.
+        if SummaryStatsFile::has_index(&self.formatted_file) {
+            SummaryStatsFile::query(&self.formatted_file, region)
+        } else {
+            SummaryStatsFile::scan_region(&self.formatted_file, region)
+        }
+    }
+}
+
+
+// Parse a 'chrom:start-end' region into its components.
+fn _parse_region(region: &str) -> Result<(String, u32, u32), String> {
+    let colon: Vec<&str> = region.splitn(2, ':').collect();
+    if colon.len() != 2 {
+        return Err(format!("Malformed region '{}'", region));
+    }
+
+    let dash: Vec<&str> = colon[1].splitn(2, '-').collect();
+    if dash.len() != 2 {
+        return Err(format!("Malformed region '{}'", region));
+    }
+
+    let start = dash[0].parse()
+        .map_err(|_| format!("Malformed region start in '{}'", region))?;
+    let end = dash[1].parse()
+        .map_err(|_| format!("Malformed region end in '{}'", region))?;
+
+    Ok((colon[0].to_string(), start, end))
+}
+
+
+// True when the record is on `chrom` (column index 1) and its position
+// (column index 2) falls within [start, end].
+fn _line_in_region(line: &str, chrom: &str, start: u32, end: u32) -> bool {
+    let mut fields = line.split('\t').skip(1);
+
+    match fields.next() {
+        Some(c) if c.trim() == chrom => (),
+        _ => return false,
+    }
+
+    match fields.next().and_then(|p| p.trim().parse::<u32>().ok()) {
+        Some(pos) => pos >= start && pos <= end,
+        None => false,
     }
 }
 
@@ -247,13 +303,123 @@ impl SummaryStatsFile {
         })
     }
 
-    pub fn read_file(filename: &str)
+    // True when a tabix (.tbi) or CSI (.csi) index sits next to the file.
+    pub fn has_index(filename: &str) -> bool {
+        Path::new(&format!("{}.csi", filename)).exists() ||
+        Path::new(&format!("{}.tbi", filename)).exists()
+    }
+
+    // Indexed region query: seek directly to the bgzf chunks covering the
+    // region and keep only the records that actually overlap it. Only the
+    // overlapping portion of the file is decompressed.
+    pub fn query(filename: &str, region: &str)
+        -> Result<SummaryStatsFile, String>
+    {
+        use noodles::bgzf;
+        use noodles::core::Position;
+        use noodles::csi::{self, BinningIndex};
+
+        let (chrom, start, end) = _parse_region(region)?;
+
+        // A CSI index takes precedence over the classic tabix index.
+        let csi_path = format!("{}.csi", filename);
+        let index = if Path::new(&csi_path).exists() {
+            csi::read(csi_path).map_err(|e| e.to_string())?
+        } else {
+            noodles::tabix::read(format!("{}.tbi", filename))
+                .map_err(|e| e.to_string())?
+        };
+
+        // Resolve the reference-sequence id from the index header.
+        let header = index.header()
+            .ok_or_else(|| "Index is missing a header.".to_string())?;
+        let ref_id = header.reference_sequence_names()
+            .get_index_of(chrom.as_bytes())
+            .ok_or_else(|| format!("'{}' is absent from the index.", chrom))?;
+
+        let q_start = Position::try_from(start as usize)
+            .map_err(|e| e.to_string())?;
+        let q_end = Position::try_from(end as usize)
+            .map_err(|e| e.to_string())?;
+
+        let chunks = index.query(ref_id, q_start..=q_end)
+            .map_err(|e| e.to_string())?;
+
+        let mut reader = bgzf::Reader::new(
+            File::open(filename).map_err(|e| e.to_string())?
+        );
+
+        let mut lines: Vec<std::io::Result<String>> = Vec::new();
+        for chunk in chunks {
+            reader.seek(chunk.start()).map_err(|e| e.to_string())?;
+
+            loop {
+                let mut line = String::new();
+                if reader.read_line(&mut line).map_err(|e| e.to_string())? == 0 {
+                    break;
+                }
+
+                // The chunk boundaries are coarse and may span adjacent
+                // references sharing a bgzf block; keep only records on the
+                // requested chromosome whose position truly overlaps.
+                if _line_in_region(&line, &chrom, start, end) {
+                    lines.push(Ok(line.trim_end().to_string()));
+                }
+
+                if reader.virtual_position() >= chunk.end() {
+                    break;
+                }
+            }
+        }
+
+        Ok(SummaryStatsFile { iter: Box::new(lines.into_iter()) })
+    }
+
+    // Linear-scan fallback used when no index is present; reads the whole
+    // file and retains the records overlapping the region.
+    pub fn scan_region(filename: &str, region: &str)
         -> Result<SummaryStatsFile, String>
     {
+        let (chrom, start, end) = _parse_region(region)?;
+
         let f = File::open(filename)
-            .expect(&format!("Couldn't open file: {:?}", filename));
+            .map_err(|e| format!("Couldn't open file {:?}: {}", filename, e))?;
 
         let mut iter = BufReader::new(f).lines();
+        iter.next();  // Skip the header.
+
+        let lines: Vec<std::io::Result<String>> = iter
+            .filter(|res| match res {
+                Ok(line) => _line_in_region(line, &chrom, start, end),
+                Err(_) => true,
+            })
+            .collect();
+
+        Ok(SummaryStatsFile { iter: Box::new(lines.into_iter()) })
+    }
+
+    pub fn read_file(filename: &str)
+        -> Result<SummaryStatsFile, String>
+    {
+        // Sniff the leading two bytes so both plain-text and bgzipped
+        // statistics files (the form carried alongside tabix/CSI indices)
+        // can be scanned transparently; bgzf shares the gzip magic 1f 8b.
+        let mut magic = [0u8; 2];
+        File::open(filename)
+            .map_err(|e| format!("Couldn't open file {:?}: {}", filename, e))?
+            .read_exact(&mut magic)
+            .map_err(|e| format!("Couldn't read file {:?}: {}", filename, e))?;
+
+        let f = File::open(filename)
+            .map_err(|e| format!("Couldn't open file {:?}: {}", filename, e))?;
+
+        let reader: Box<dyn BufRead> = if magic == [0x1f, 0x8b] {
+            Box::new(BufReader::new(noodles::bgzf::Reader::new(f)))
+        } else {
+            Box::new(BufReader::new(f))
+        };
+
+        let mut iter = reader.lines();
 
         // Because there is a header, we skip it and assume the columns
         // are defined as per the spec.
@@ -279,7 +445,8 @@ impl Iterator for SummaryStatsFile {
                 coded_allele: code,
                 effect: str_vec[5].parse().unwrap(),
                 se: str_vec[6].parse().unwrap(),
-                p: str_vec[7].parse().unwrap()
+                p: str_vec[7].parse().unwrap(),
+                strand_flipped: false
             };
 
             return Some(Ok(assoc));
@@ -296,7 +463,84 @@ pub struct AssociationStat {
     pub coded_allele: CodedAllele,
     pub effect: f32,
     pub se: f32,
-    pub p: f32
+    pub p: f32,
+
+    // Set when the statistics were matched after reverse-complementing the
+    // query alleles (i.e. the datasets are stored on opposite strands).
+    pub strand_flipped: bool
+}
+
+
+fn _complement_base(b: char) -> Option<char> {
+    match b.to_ascii_uppercase() {
+        'A' => Some('T'),
+        'T' => Some('A'),
+        'C' => Some('G'),
+        'G' => Some('C'),
+        _ => None,
+    }
+}
+
+
+// Reverse-complement an allele string, returning None for non-ACGT alleles.
+pub fn reverse_complement(allele: &str) -> Option<String> {
+    allele.chars().rev().map(_complement_base).collect()
+}
+
+
+// A biallelic SNP is palindromic when its two alleles are complementary
+// (A/T or C/G), so the strand cannot be inferred from the alleles alone.
+pub fn is_palindromic(a1: &str, a2: &str) -> bool {
+    a1.len() == 1 && a2.len() == 1 &&
+        reverse_complement(a1).map_or(false, |rc| rc == a2)
+}
+
+
+// Decide whether the query and observed variants describe the same locus,
+// allowing for a strand flip. Returns Some(true) when a reverse-complement
+// was required, Some(false) for a same-strand match, and None when the
+// alleles are irreconcilable or a palindromic variant cannot be oriented.
+//
+// Palindromic (A/T, C/G) SNPs are skipped with a warning because the strand
+// cannot be resolved from the alleles alone, and no allele-frequency source
+// is threaded through the statistics files to break the tie.
+pub fn harmonize_variant(query: &Variant, observed: &Variant)
+    -> Option<bool>
+{
+    if query.chrom != observed.chrom || query.position != observed.position {
+        return None;
+    }
+
+    let (q1, q2) = (&query.alleles.0, &query.alleles.1);
+    let (o1, o2) = (&observed.alleles.0, &observed.alleles.1);
+
+    // Palindromic SNPs are ambiguous and cannot be oriented; skip with a
+    // warning rather than risk flipping the effect in the wrong direction.
+    if is_palindromic(q1, q2) {
+        eprintln!(
+            "WARN: skipping palindromic variant {}:{} {}/{} (strand \
+             cannot be resolved)",
+            query.chrom.name, query.position, q1, q2
+        );
+        return None;
+    }
+
+    // Same strand: alleles match directly, in either order.
+    if (q1 == o1 && q2 == o2) || (q1 == o2 && q2 == o1) {
+        return Some(false);
+    }
+
+    // Opposite strand: compare against the reverse-complemented query.
+    match (reverse_complement(q1), reverse_complement(q2)) {
+        (Some(rc1), Some(rc2)) => {
+            if (rc1 == *o1 && rc2 == *o2) || (rc1 == *o2 && rc2 == *o1) {
+                Some(true)
+            } else {
+                None
+            }
+        },
+        _ => None,
+    }
 }
 
 
@@ -10,9 +10,10 @@ extern crate clap;
 extern crate csv;
 
 extern crate genepa_rs;
+extern crate noodles;
 
 use std::error::Error;
-use std::io::{Read};
+use std::io::{Read, BufRead, BufReader};
 use std::fs::File;
 use std::collections::{HashMap, HashSet};
 use clap::{Arg, App, SubCommand, AppSettings};
@@ -56,68 +57,231 @@ fn _parse_variant_from_args(args: &clap::ArgMatches)
 }
 
 
-// Write a CSV entry for a statistics result on a given dataset and component.
-fn _write_csv_row(writer: &mut csv::Writer<std::fs::File>,
-                  dataset: &gwasss::Dataset,
-                  component: &gwasss::Component,
-                  stat: &gwasss::AssociationStat) {
+// Columns of the long extraction table, shared by the csv and tsv sinks.
+const OUTPUT_COLUMNS: &[&str] = &[
+    "dataset_variant_name", "chrom", "pos",
+    "reference_allele", "coded_allele",
+    "dataset_name", "component_name", "population", "sex",
+    "effect_type", "effect", "se", "p", "strand_flipped"
+];
+
+
+fn _init_writer<I, T>(path: &str, header: I) -> csv::Writer<std::fs::File>
+    where I: IntoIterator<Item=T>, T: AsRef<[u8]>
+{
+    let mut writer = csv::WriterBuilder::new().from_path(path)
+        .expect("Could not open file for writing");
+
+    writer.write_record(header).expect("Could not write header");
+
+    writer
+}
+
+
+// Sink for extracted associations. Implementors decide how a single result is
+// serialized, letting the extraction commands target different formats.
+trait OutputWriter {
+    fn write_row(&mut self, dataset: &gwasss::Dataset,
+                 component: &gwasss::Component,
+                 stat: &gwasss::AssociationStat);
+
+    fn flush(&mut self);
+}
+
+
+// Format a statistics result as the fields of one row of the long table; the
+// field separator is chosen by the writer that receives the row.
+fn _format_row(dataset: &gwasss::Dataset,
+               component: &gwasss::Component,
+               stat: &gwasss::AssociationStat) -> Vec<String> {
 
     // Format small p-values.
     let fmt_p = if stat.p < 0.05 {
         format!("{:e}", stat.p)
     } else { stat.p.to_string() };
 
-    writer.write_record(&[
-        &stat.variant.name,
-        &stat.variant.chrom.name,
-        &stat.variant.position.to_string(),
-        stat.get_reference_allele(),
-        stat.get_coded_allele(),
-        &dataset.name,
-        &component.trait_name,
-        &component.population.to_string(),
-        &component.sex.to_string(),
-        &component.effect_type.to_string(),
-        &stat.effect.to_string(),
-        &stat.se.to_string(),
-        &fmt_p
-    ]).expect("Could not write variant to output file.");
+    vec![
+        stat.variant.name.clone(),
+        stat.variant.chrom.name.clone(),
+        stat.variant.position.to_string(),
+        stat.get_reference_allele().to_string(),
+        stat.get_coded_allele().to_string(),
+        dataset.name.clone(),
+        component.trait_name.clone(),
+        component.population.to_string(),
+        component.sex.to_string(),
+        component.effect_type.to_string(),
+        stat.effect.to_string(),
+        stat.se.to_string(),
+        fmt_p,
+        stat.strand_flipped.to_string(),
+    ]
+}
+
 
+// Comma- or tab-separated long table. The bgzip variant keeps the concrete
+// bgzf writer so its EOF block can be finalized explicitly on flush; a
+// Box<dyn Write> would hide the type and risk a truncated BGZF stream.
+enum DelimitedWriter {
+    Plain(csv::Writer<File>),
+    Bgzip(csv::Writer<noodles::bgzf::Writer<File>>),
 }
 
+impl DelimitedWriter {
+    fn new(path: &str, delimiter: u8, bgzip: bool) -> DelimitedWriter {
+        let file = File::create(path)
+            .expect("Could not open file for writing");
+
+        let builder = || csv::WriterBuilder::new().delimiter(delimiter);
+
+        if bgzip {
+            let mut writer = builder()
+                .from_writer(noodles::bgzf::Writer::new(file));
+            writer.write_record(OUTPUT_COLUMNS)
+                .expect("Could not write header");
+            DelimitedWriter::Bgzip(writer)
+        } else {
+            let mut writer = builder().from_writer(file);
+            writer.write_record(OUTPUT_COLUMNS)
+                .expect("Could not write header");
+            DelimitedWriter::Plain(writer)
+        }
+    }
+}
 
-fn _init_writer<I, T>(path: &str, header: I) -> csv::Writer<std::fs::File>
-    where I: IntoIterator<Item=T>, T: AsRef<[u8]>
-{
-    let mut writer = csv::WriterBuilder::new().from_path(path)
-        .expect("Could not open file for writing");
+impl OutputWriter for DelimitedWriter {
+    fn write_row(&mut self, dataset: &gwasss::Dataset,
+                 component: &gwasss::Component,
+                 stat: &gwasss::AssociationStat) {
+        let row = _format_row(dataset, component, stat);
+        let result = match self {
+            DelimitedWriter::Plain(w) => w.write_record(&row),
+            DelimitedWriter::Bgzip(w) => w.write_record(&row),
+        };
+        result.expect("Could not write variant to output file.");
+    }
 
-    writer.write_record(header).expect("Could not write header");
+    fn flush(&mut self) {
+        match self {
+            DelimitedWriter::Plain(w) => w.flush().expect("Broken flush"),
+            DelimitedWriter::Bgzip(w) => {
+                w.flush().expect("Broken flush");
+                // Drain the csv buffer into the bgzf writer and emit the BGZF
+                // EOF block so the '.gz' stream is not left truncated.
+                w.get_mut().try_finish().expect("Could not finalize BGZF");
+            },
+        }
+    }
+}
 
-    writer
+
+// One VCF record per extracted association; the effect/SE/p and the
+// dataset/component/population/sex metadata are encoded as INFO fields so the
+// result can feed VCF-aware downstream tooling.
+struct VcfWriter {
+    writer: noodles::vcf::Writer<File>,
 }
 
-fn _init_writer_default(path: &str) -> csv::Writer<std::fs::File> {
-    _init_writer(path, &[
-        "dataset_variant_name", "chrom", "pos",
-        "reference_allele", "coded_allele",
-        "dataset_name", "component_name", "population", "sex",
-        "effect_type", "effect", "se", "p"
-    ])
+impl VcfWriter {
+    fn new(path: &str) -> VcfWriter {
+        use noodles::vcf::{self, header::record::value::{map::Info, Map}};
+        use noodles::vcf::header::record::value::map::info::{Number, Type};
+
+        let file = File::create(path)
+            .expect("Could not open file for writing");
+
+        // Advertise the INFO fields we populate for every record.
+        let info = |number, ty, desc: &str| {
+            Map::<Info>::new(number, ty, desc)
+        };
+        let header = vcf::Header::builder()
+            .add_info("EFFECT".parse().unwrap(),
+                      info(Number::Count(1), Type::Float, "Effect size"))
+            .add_info("SE".parse().unwrap(),
+                      info(Number::Count(1), Type::Float, "Standard error"))
+            .add_info("P".parse().unwrap(),
+                      info(Number::Count(1), Type::Float, "P-value"))
+            .add_info("DATASET".parse().unwrap(),
+                      info(Number::Count(1), Type::String, "Dataset name"))
+            .add_info("COMPONENT".parse().unwrap(),
+                      info(Number::Count(1), Type::String, "Component name"))
+            .add_info("POP".parse().unwrap(),
+                      info(Number::Count(1), Type::String, "Population"))
+            .add_info("SEX".parse().unwrap(),
+                      info(Number::Count(1), Type::String, "Sex"))
+            .build();
+
+        let mut writer = vcf::Writer::new(file);
+        writer.write_header(&header).expect("Could not write VCF header");
+
+        VcfWriter { writer }
+    }
+}
+
+impl OutputWriter for VcfWriter {
+    fn write_row(&mut self, _dataset: &gwasss::Dataset,
+                 component: &gwasss::Component,
+                 stat: &gwasss::AssociationStat) {
+        use noodles::vcf::record::Position;
+
+        let info = format!(
+            "EFFECT={};SE={};P={};DATASET={};COMPONENT={};POP={};SEX={}",
+            stat.effect, stat.se, stat.p, _dataset.name, component.trait_name,
+            component.population, component.sex
+        );
+
+        let record = noodles::vcf::Record::builder()
+            .set_chromosome(stat.variant.chrom.name.parse()
+                .expect("Invalid chromosome"))
+            .set_position(Position::from(stat.variant.position as usize))
+            .set_reference_bases(stat.get_reference_allele().parse()
+                .expect("Invalid reference allele"))
+            .set_alternate_bases(stat.get_coded_allele().parse()
+                .expect("Invalid coded allele"))
+            .set_info(info.parse().expect("Invalid INFO field"))
+            .build()
+            .expect("Could not build VCF record");
+
+        self.writer.write_record(&record)
+            .expect("Could not write variant to output file.");
+    }
+
+    fn flush(&mut self) {
+        use std::io::Write;
+        self.writer.get_mut().flush().expect("Broken flush");
+    }
+}
+
+
+// Build the output sink requested on the command line. CSV is the default so
+// existing invocations keep their behavior.
+fn _make_writer(format: &str, path: &str) -> Box<dyn OutputWriter> {
+    match format {
+        "csv" => Box::new(DelimitedWriter::new(path, b',', false)),
+        "tsv" => {
+            // Transparently bgzip when the destination ends in '.gz'.
+            let bgzip = path.ends_with(".gz");
+            Box::new(DelimitedWriter::new(path, b'\t', bgzip))
+        },
+        "vcf" => Box::new(VcfWriter::new(path)),
+        _ => panic!("Unknown output format '{}'", format),
+    }
 }
 
 
 fn cmd_extract_region(datasets: Vec<Dataset>, args: &clap::ArgMatches) {
     let region = args.value_of("region").unwrap().to_string();
-    let mut writer = _init_writer_default(args.value_of("output").unwrap());
+    let mut writer = _make_writer(
+        args.value_of("output_format").unwrap(),
+        args.value_of("output").unwrap()
+    );
 
     for dataset in datasets.iter() {
         for component in dataset.components.iter() {
-            for mut result in component.get_stats_for_region(&region) {
+            for result in component.get_stats_for_region(&region) {
                 match result {
-                    Ok(ref mut stat) => {
-                        _write_csv_row(&mut writer, &dataset, &component,
-                                       stat);
+                    Ok(ref stat) => {
+                        writer.write_row(&dataset, &component, stat);
                     },
                     Err(e) => println!("{} :: {:?}", e, component)
                 }
@@ -125,6 +289,7 @@ fn cmd_extract_region(datasets: Vec<Dataset>, args: &clap::ArgMatches) {
         }
     }
 
+    writer.flush();
 }
 
 
@@ -133,7 +298,10 @@ fn cmd_extract_variant(datasets: Vec<Dataset>, args: &clap::ArgMatches) {
     let v = _parse_variant_from_args(args)
         .expect("Could not parse variant from command arguments.");
 
-    let mut writer = _init_writer_default(args.value_of("output").unwrap());
+    let mut writer = _make_writer(
+        args.value_of("output_format").unwrap(),
+        args.value_of("output").unwrap()
+    );
 
     // Extract variant if possible for every dataset.
     for dataset in datasets.iter() {
@@ -141,27 +309,130 @@ fn cmd_extract_variant(datasets: Vec<Dataset>, args: &clap::ArgMatches) {
             match component.get_stats_for_variant(
                 &v, args.value_of("coded_allele").unwrap()
             ) {
-                Ok(ref mut stat) => {
+                Ok(ref stat) => {
                     // The variant was found.
-                    _write_csv_row(&mut writer, &dataset, &component, stat)
+                    writer.write_row(&dataset, &component, stat)
                 },
                 Err(e) => println!("{} :: {:?}", e, component)
             }
         }
     }
 
-    writer.flush().expect("Broken flush");
+    writer.flush();
+}
+
 
+// Turn a single VCF record into an OrderedAllelesVariant, skipping (with a
+// warning) multiallelic sites rather than panicking. Returns None when the
+// site is skipped.
+fn _vcf_record_to_variant(record: &noodles::vcf::Record)
+    -> Option<OrderedAllelesVariant>
+{
+    let alts = record.alternate_bases();
+    if alts.len() != 1 {
+        println!(
+            "WARN: skipping multiallelic site {}:{}",
+            record.chromosome(), record.position()
+        );
+        return None;
+    }
+
+    let reference_allele = record.reference_bases().to_string()
+        .to_uppercase();
+    let coded_allele = alts[0].to_string().to_uppercase();
+
+    let v = Variant::new(
+        String::from(""),  // VCF ids are unused downstream.
+        record.chromosome().to_string(),
+        usize::from(record.position()) as u32,
+        (reference_allele, coded_allele.clone())
+    );
+
+    // ALT is the coded allele (A1); point a1_idx at wherever Variant::new
+    // placed it.
+    let a1_idx = if v.alleles.0 == coded_allele { 0 } else { 1 };
+    Some(OrderedAllelesVariant { variant: v, a1_idx })
 }
 
 
-fn cmd_extract_variants(datasets: Vec<Dataset>, args: &clap::ArgMatches) {
-    // Read the variants using the right reader.
-    let format = args.value_of("variants_format").unwrap();
-    let filename = args.value_of("variants_filename").unwrap();
+// Read variants from a (possibly bgzipped) VCF or a binary BCF file.
+//
+// CHROM/POS and the REF/ALT pair are used to build an OrderedAllelesVariant
+// whose coded allele (A1) is the ALT allele, mirroring how the bim and stat
+// readers feed cmd_extract_variants. Multiallelic sites and records that fail
+// to parse are skipped with a warning rather than panicking.
+fn _read_vcf_variants(filename: &str) -> Vec<OrderedAllelesVariant> {
+    use noodles::{bcf, vcf};
+
+    let mut variants: Vec<OrderedAllelesVariant> = Vec::new();
+
+    // BCF is a binary format with its own reader; dispatch on the extension
+    // so a '.bcf' isn't mistaken for a bgzipped VCF by the magic sniff below.
+    if filename.ends_with(".bcf") {
+        let mut reader = bcf::Reader::new(
+            File::open(filename)
+                .unwrap_or_else(|e| panic!("Couldn't open file {:?}: {}",
+                                           filename, e))
+        );
+        let header = reader.read_header().expect("Could not read BCF header");
+
+        for result in reader.records(&header) {
+            match result {
+                Ok(record) => {
+                    if let Some(oav) = _vcf_record_to_variant(&record) {
+                        variants.push(oav);
+                    }
+                },
+                Err(e) => println!("WARN: skipping unreadable BCF record: {}", e),
+            }
+        }
+
+        return variants;
+    }
+
+    // Sniff the first two bytes to transparently support both plain VCF and
+    // bgzipped '.vcf.gz' inputs (bgzf starts with the gzip magic 1f 8b). A
+    // short read could misclassify the file, so insist on filling the buffer.
+    let mut magic = [0u8; 2];
+    File::open(filename)
+        .unwrap_or_else(|e| panic!("Couldn't open file {:?}: {}", filename, e))
+        .read_exact(&mut magic)
+        .expect("Could not read VCF file");
+
+    let inner: Box<dyn BufRead> = if magic == [0x1f, 0x8b] {
+        Box::new(noodles::bgzf::Reader::new(
+            File::open(filename).expect("Could not open VCF file")
+        ))
+    } else {
+        Box::new(BufReader::new(
+            File::open(filename).expect("Could not open VCF file")
+        ))
+    };
+
+    let mut reader = vcf::Reader::new(inner);
+    let header = reader.read_header().expect("Could not read VCF header");
 
-    let variants: Vec<OrderedAllelesVariant> = if format == "vcf" {
-        panic!("Can't read VCF files yet.");
+    for result in reader.records(&header) {
+        match result {
+            Ok(record) => {
+                if let Some(oav) = _vcf_record_to_variant(&record) {
+                    variants.push(oav);
+                }
+            },
+            Err(e) => println!("WARN: skipping unreadable VCF record: {}", e),
+        }
+    }
+
+    variants
+}
+
+
+// Read a set of variants from a file using the reader matching its format.
+// The coded allele (A1) is carried through as OrderedAllelesVariant.a1_idx.
+fn _read_variants(format: &str, filename: &str) -> Vec<OrderedAllelesVariant> {
+    if format == "vcf" {
+        // Read variants from a plain or bgzipped VCF, coding the ALT allele.
+        _read_vcf_variants(filename)
     }
     else if format == "bim" {
         // Assume coded is A1 (minor allele).
@@ -190,32 +461,49 @@ fn cmd_extract_variants(datasets: Vec<Dataset>, args: &clap::ArgMatches) {
     }
     else {
         panic!(format!("Unknown format '{}'", format));
-    };
+    }
+}
+
+
+// Return the coded allele (A1) of an OrderedAllelesVariant.
+fn _coded_allele(oav: &OrderedAllelesVariant) -> &str {
+    if oav.a1_idx == 0 {
+        &oav.variant.alleles.0
+    }
+    else if oav.a1_idx == 1 {
+        &oav.variant.alleles.1
+    }
+    else {
+        panic!("Bad alleles.");
+    }
+}
+
+
+fn cmd_extract_variants(datasets: Vec<Dataset>, args: &clap::ArgMatches) {
+    // Read the variants using the right reader.
+    let format = args.value_of("variants_format").unwrap();
+    let filename = args.value_of("variants_filename").unwrap();
+
+    let variants = _read_variants(format, filename);
 
     // Get an output writer.
-    let mut writer = _init_writer_default(args.value_of("output").unwrap());
+    let mut writer = _make_writer(
+        args.value_of("output_format").unwrap(),
+        args.value_of("output").unwrap()
+    );
 
     for dataset in datasets.iter() {
         for component in dataset.components.iter() {
             // Iterate over ordered allele variants.
             // The "coded" allele is always 'A1'.
             for oav in variants.iter() {
-                let coded_allele = if oav.a1_idx == 0 {
-                    &oav.variant.alleles.0
-                }
-                else if oav.a1_idx == 1 {
-                    &oav.variant.alleles.1
-                }
-                else {
-                    panic!("Bad alleles.");
-                };
+                let coded_allele = _coded_allele(oav);
 
                 match component.get_stats_for_variant(
                     &oav.variant, coded_allele
                 ) {
-                    Ok(ref mut stat) => {
-                        _write_csv_row(&mut writer, &dataset, &component,
-                                       stat);
+                    Ok(ref stat) => {
+                        writer.write_row(&dataset, &component, stat);
                     },
                     Err(e) => println!("WARN: {} - {:?}", &oav.variant, e)
                 }
@@ -223,6 +511,344 @@ fn cmd_extract_variants(datasets: Vec<Dataset>, args: &clap::ArgMatches) {
         }
     }
 
+    writer.flush();
+}
+
+
+// Complementary error function (Numerical Recipes rational approximation).
+fn _erfc(x: f64) -> f64 {
+    let z = x.abs();
+    let t = 1.0 / (1.0 + 0.5 * z);
+    let ans = t * (-z * z - 1.26551223 + t * (1.00002368 + t * (0.37409196 +
+        t * (0.09678418 + t * (-0.18628806 + t * (0.27886807 + t * (-1.13520398 +
+        t * (1.48851587 + t * (-0.82215223 + t * 0.17087277))))))))).exp();
+
+    if x >= 0.0 { ans } else { 2.0 - ans }
+}
+
+
+// Two-sided p-value of a z-score under the standard normal.
+fn _two_sided_normal_p(z: f64) -> f64 {
+    _erfc(z.abs() / std::f64::consts::SQRT_2)
+}
+
+
+// Result of pooling K harmonized effects by fixed-effects inverse-variance
+// weighting, together with the usual heterogeneity statistics.
+struct MetaResult {
+    beta: f64,
+    se: f64,
+    p: f64,
+    q: f64,
+    i2: f64,
+    tau2: f64,
+    k: usize,
+}
+
+
+// Fixed-effects inverse-variance-weighted meta-analysis with Cochran's Q,
+// I² and the DerSimonian–Laird tau² estimate.
+fn _ivw(betas: &[f64], ses: &[f64]) -> MetaResult {
+    let k = betas.len();
+    let w: Vec<f64> = ses.iter().map(|s| 1.0 / (s * s)).collect();
+
+    let sum_w: f64 = w.iter().sum();
+    let sum_wb: f64 = w.iter().zip(betas).map(|(w, b)| w * b).sum();
+
+    let beta = sum_wb / sum_w;
+    let se = (1.0 / sum_w).sqrt();
+    let p = _two_sided_normal_p(beta / se);
+
+    // Cochran's Q on K-1 degrees of freedom.
+    let df = (k - 1) as f64;
+    let q: f64 = w.iter().zip(betas)
+        .map(|(w, b)| w * (b - beta).powi(2))
+        .sum();
+
+    let i2 = if q > 0.0 { ((q - df) / q).max(0.0) } else { 0.0 };
+
+    // DerSimonian–Laird moment estimator of the between-study variance.
+    let sum_w2: f64 = w.iter().map(|w| w * w).sum();
+    let c = sum_w - sum_w2 / sum_w;
+    let tau2 = if c > 0.0 { ((q - df) / c).max(0.0) } else { 0.0 };
+
+    MetaResult { beta, se, p, q, i2, tau2, k }
+}
+
+
+// Express an association's effect on the additive (beta) scale so that
+// effects from OR/HR and beta components can be pooled together.
+fn _effect_as_beta(stat: &gwasss::AssociationStat,
+                   effect_type: &gwasss::EffectType) -> f64 {
+    match effect_type {
+        gwasss::EffectType::OR | gwasss::EffectType::HR => {
+            (stat.effect as f64).ln()
+        },
+        _ => stat.effect as f64,
+    }
+}
+
+
+// Resolve '--inputs dataset:trait, ...' specifiers to component references.
+fn _resolve_inputs<'a>(datasets: &'a [Dataset], specs: clap::Values)
+    -> Vec<(&'a Dataset, &'a gwasss::Component)>
+{
+    let mut resolved = Vec::new();
+
+    for spec in specs {
+        let parts: Vec<&str> = spec.splitn(2, ':').collect();
+        if parts.len() != 2 {
+            panic!("Malformed input specifier '{}'", spec);
+        }
+
+        let (ds_name, trait_name) = (parts[0], parts[1]);
+
+        let hit = datasets.iter().find_map(|d| {
+            if d.name != ds_name { return None; }
+            d.components.iter()
+                .find(|c| c.trait_name == trait_name)
+                .map(|c| (d, c))
+        });
+
+        match hit {
+            Some(pair) => resolved.push(pair),
+            None => println!("WARN: no component matches input '{}'", spec),
+        }
+    }
+
+    resolved
+}
+
+
+// Resolve a single 'dataset:trait' specifier to a component reference.
+fn _resolve_component<'a>(datasets: &'a [Dataset], spec: &str)
+    -> (&'a Dataset, &'a gwasss::Component)
+{
+    let parts: Vec<&str> = spec.splitn(2, ':').collect();
+    if parts.len() != 2 {
+        panic!("Malformed component specifier '{}'", spec);
+    }
+
+    datasets.iter().find_map(|d| {
+        if d.name != parts[0] { return None; }
+        d.components.iter()
+            .find(|c| c.trait_name == parts[1])
+            .map(|c| (d, c))
+    }).unwrap_or_else(|| panic!("No component matches '{}'", spec))
+}
+
+
+// Inverse-variance-weighted Pearson correlation between two aligned series.
+// Returns None when either side has no variance (e.g. identical betas), as the
+// correlation is undefined there and would otherwise evaluate to NaN.
+fn _weighted_pearson(bx: &[f64], by: &[f64], w: &[f64]) -> Option<f64> {
+    let sum_w: f64 = w.iter().sum();
+    let mx: f64 = bx.iter().zip(w).map(|(b, w)| b * w).sum::<f64>() / sum_w;
+    let my: f64 = by.iter().zip(w).map(|(b, w)| b * w).sum::<f64>() / sum_w;
+
+    let (mut cov, mut vx, mut vy) = (0.0, 0.0, 0.0);
+    for i in 0..bx.len() {
+        let dx = bx[i] - mx;
+        let dy = by[i] - my;
+        cov += w[i] * dx * dy;
+        vx += w[i] * dx * dx;
+        vy += w[i] * dy * dy;
+    }
+
+    if vx <= 0.0 || vy <= 0.0 {
+        return None;
+    }
+
+    Some(cov / (vx.sqrt() * vy.sqrt()))
+}
+
+
+// Survival function of a chi-square with even degrees of freedom (closed
+// form), used to turn a Fisher combined statistic into a p-value.
+fn _chi2_sf_even(x: f64, df: usize) -> f64 {
+    let k = df / 2;
+    let mut term = 1.0;
+    let mut sum = 1.0;
+    for i in 1..k {
+        term *= (x / 2.0) / i as f64;
+        sum += term;
+    }
+
+    ((-x / 2.0).exp() * sum).min(1.0)
+}
+
+
+// Combine independent p-values with Fisher's method: chi2 = -2·Σ ln(p_i) on
+// 2n degrees of freedom. Returns (chi2, combined_p).
+fn _fisher_combined_p(pvals: &[f64]) -> (f64, f64) {
+    let chi2: f64 = -2.0 * pvals.iter()
+        .map(|p| p.max(1e-300).ln())
+        .sum::<f64>();
+
+    (chi2, _chi2_sf_even(chi2, 2 * pvals.len()))
+}
+
+
+fn cmd_correlation(datasets: Vec<Dataset>, args: &clap::ArgMatches) {
+    let x = _resolve_component(&datasets, args.value_of("x").unwrap());
+    let y = _resolve_component(&datasets, args.value_of("y").unwrap());
+
+    let p_threshold: f64 = args.value_of("p_threshold").unwrap()
+        .parse().expect("Could not parse --p-threshold.");
+
+    // Build the list of (variant, coded allele) to test. The variant set is
+    // restricted by --region, read from a file, or taken as all of X.
+    let selection: Vec<(Variant, String)> = if let Some(region) =
+        args.value_of("region")
+    {
+        x.1.get_stats_for_region(region)
+            .expect("Could not query region on X.")
+            .filter_map(|r| r.ok())
+            .map(|s| { let c = s.get_coded_allele().to_string(); (s.variant, c) })
+            .collect()
+    }
+    else if let Some(filename) = args.value_of("variants_filename") {
+        _read_variants(args.value_of("variants_format").unwrap(), filename)
+            .into_iter()
+            .map(|oav| { let c = _coded_allele(&oav).to_string(); (oav.variant, c) })
+            .collect()
+    }
+    else if args.is_present("all_variants") {
+        // --all-variants: enumerate every variant stored for X.
+        gwasss::SummaryStatsFile::read_file(&x.1.formatted_file)
+            .expect("Could not read X statistics.")
+            .filter_map(|r| r.ok())
+            .map(|s| { let c = s.get_coded_allele().to_string(); (s.variant, c) })
+            .collect()
+    }
+    else {
+        panic!("No variant selector: pass --region, --variants-filename or \
+                --all-variants.");
+    };
+
+    // Aligned effects for the correlation, plus the concordant X-side
+    // p-values for the Fisher enrichment test.
+    let (mut bx, mut by, mut w) = (Vec::new(), Vec::new(), Vec::new());
+    let mut fisher_ps: Vec<f64> = Vec::new();
+
+    for (v, coded) in selection.iter() {
+        let sx = x.1.get_stats_for_variant(v, coded);
+        let sy = y.1.get_stats_for_variant(v, coded);
+
+        if let (Ok(sx), Ok(sy)) = (sx, sy) {
+            let beta_x = _effect_as_beta(&sx, &x.1.effect_type);
+            let beta_y = _effect_as_beta(&sy, &y.1.effect_type);
+
+            bx.push(beta_x);
+            by.push(beta_y);
+            w.push(1.0 / ((sx.se as f64).powi(2) + (sy.se as f64).powi(2)));
+
+            // Sign-concordance among X-significant variants.
+            if (sx.p as f64) <= p_threshold && beta_x.signum() == beta_y.signum() {
+                fisher_ps.push(sx.p as f64);
+            }
+        }
+    }
+
+    let n_shared = bx.len();
+    if n_shared < 2 {
+        println!("Fewer than two shared variants; nothing to correlate.");
+        return;
+    }
+
+    let r = _weighted_pearson(&bx, &by, &w);
+    let (chi2, fisher_p) = _fisher_combined_p(&fisher_ps);
+
+    println!("Shared variants used     : {}", n_shared);
+    match r {
+        Some(r) => println!("IVW Pearson correlation  : {:.6}", r),
+        None => println!("IVW Pearson correlation  : undefined (no variance)"),
+    }
+    println!("Concordant & significant : {}", fisher_ps.len());
+    println!("Fisher chi2 ({} df)       : {:.4}", 2 * fisher_ps.len(), chi2);
+    println!("Fisher combined p        : {:e}", fisher_p);
+}
+
+
+fn cmd_meta_analyze(datasets: Vec<Dataset>, args: &clap::ArgMatches) {
+    let inputs = _resolve_inputs(&datasets, args.values_of("inputs").unwrap());
+    if inputs.len() < 2 {
+        panic!("meta-analyze needs at least two resolvable inputs.");
+    }
+
+    let variants = _read_variants(
+        args.value_of("variants_format").unwrap(),
+        args.value_of("variants_filename").unwrap()
+    );
+
+    // The meta-analysis output reuses the standard long layout up to the p
+    // column and appends the pooled heterogeneity statistics; the per-input
+    // strand_flipped column is not written.
+    let mut writer = _init_writer(args.value_of("output").unwrap(), &[
+        "dataset_variant_name", "chrom", "pos",
+        "reference_allele", "coded_allele",
+        "dataset_name", "component_name", "population", "sex",
+        "effect_type", "effect", "se", "p",
+        "q", "i2", "tau2", "n_studies"
+    ]);
+
+    for oav in variants.iter() {
+        let coded_allele = _coded_allele(oav);
+
+        // Gather every input's effect harmonized to the coded allele.
+        let mut betas: Vec<f64> = Vec::new();
+        let mut ses: Vec<f64> = Vec::new();
+
+        for (_dataset, component) in inputs.iter() {
+            match component.get_stats_for_variant(&oav.variant, coded_allele) {
+                Ok(stat) => {
+                    betas.push(_effect_as_beta(&stat, &component.effect_type));
+                    ses.push(stat.se as f64);
+                },
+                Err(e) => println!("WARN: {} - {:?}", &oav.variant, e),
+            }
+        }
+
+        // A pooled estimate requires the variant in at least two inputs.
+        if betas.len() < 2 {
+            continue;
+        }
+
+        let meta = _ivw(&betas, &ses);
+
+        let fmt_p = if meta.p < 0.05 {
+            format!("{:e}", meta.p)
+        } else { meta.p.to_string() };
+
+        // The reference allele is whichever allele is not the coded one.
+        let reference_allele = if coded_allele == oav.variant.alleles.0 {
+            &oav.variant.alleles.1
+        } else {
+            &oav.variant.alleles.0
+        };
+
+        writer.write_record(&[
+            &oav.variant.name,
+            &oav.variant.chrom.name,
+            &oav.variant.position.to_string(),
+            reference_allele,
+            coded_allele,
+            "meta_analysis",
+            &inputs[0].1.trait_name,
+            &inputs[0].1.population.to_string(),
+            &inputs[0].1.sex.to_string(),
+            "Beta",
+            &meta.beta.to_string(),
+            &meta.se.to_string(),
+            &fmt_p,
+            &meta.q.to_string(),
+            &meta.i2.to_string(),
+            &meta.tau2.to_string(),
+            &meta.k.to_string(),
+        ]).expect("Could not write variant to output file.");
+    }
+
+    writer.flush().expect("Broken flush");
 }
 
 
@@ -371,6 +997,12 @@ fn main() {
                 .help("Output filename (csv format)")
                 .takes_value(true)
                 .default_value("extracted_region.csv"))
+            .arg(Arg::with_name("output_format")
+                .long("output-format")
+                .help("Output format.")
+                .takes_value(true)
+                .possible_values(&["csv", "tsv", "vcf"])
+                .default_value("csv"))
         )
 
         .subcommand(SubCommand::with_name("extract-variants")
@@ -395,6 +1027,83 @@ fn main() {
                 .help("Output filename (csv format)")
                 .takes_value(true)
                 .default_value("extracted_variants.csv"))
+            .arg(Arg::with_name("output_format")
+                .long("output-format")
+                .help("Output format.")
+                .takes_value(true)
+                .possible_values(&["csv", "tsv", "vcf"])
+                .default_value("csv"))
+        )
+
+        .subcommand(SubCommand::with_name("correlation")
+            .about("Estimate the correlation of effect sizes shared by two \
+                    components.")
+            .arg(Arg::with_name("x")
+                .long("x")
+                .help("First component, as a 'dataset:trait' specifier.")
+                .takes_value(true)
+                .required(true))
+            .arg(Arg::with_name("y")
+                .long("y")
+                .help("Second component, as a 'dataset:trait' specifier.")
+                .takes_value(true)
+                .required(true))
+            .arg(Arg::with_name("region")
+                .long("region")
+                .help("Restrict to a chrom:start-end region.")
+                .takes_value(true))
+            .arg(Arg::with_name("variants_filename")
+                .long("variants-filename")
+                .short("i")
+                .help("Restrict to the variants contained in this file.")
+                .takes_value(true)
+                .requires("variants_format"))
+            .arg(Arg::with_name("variants_format")
+                .long("variants-format")
+                .short("f")
+                .help("File format containing the variants.")
+                .takes_value(true)
+                .possible_values(&["stat", "vcf", "bim"])
+                .requires("variants_filename"))
+            .arg(Arg::with_name("all_variants")
+                .long("all-variants")
+                .help("Use every variant present in X."))
+            .arg(Arg::with_name("p_threshold")
+                .long("p-threshold")
+                .help("X-side p-value threshold for the concordance test.")
+                .takes_value(true)
+                .default_value("5e-8"))
+        )
+
+        .subcommand(SubCommand::with_name("meta-analyze")
+            .about("Inverse-variance-weighted meta-analysis of a variant set \
+                    across several components.")
+            .arg(Arg::with_name("inputs")
+                .long("inputs")
+                .help("Components to pool, as 'dataset:trait' specifiers.")
+                .takes_value(true)
+                .use_delimiter(true)
+                .multiple(true)
+                .required(true))
+            .arg(Arg::with_name("variants_filename")
+                .long("variants-filename")
+                .short("i")
+                .help("Filename containing the input variants.")
+                .takes_value(true)
+                .required(true))
+            .arg(Arg::with_name("variants_format")
+                .long("variants-format")
+                .short("f")
+                .help("File format containing the variants.")
+                .takes_value(true)
+                .possible_values(&["stat", "vcf", "bim"])
+                .required(true))
+            .arg(Arg::with_name("output")
+                .long("output")
+                .short("o")
+                .help("Output filename (csv format)")
+                .takes_value(true)
+                .default_value("meta_analysis.csv"))
         )
 
         .subcommand(SubCommand::with_name("extract-variant")
@@ -427,6 +1136,12 @@ fn main() {
                 .help("Output filename (csv format)")
                 .takes_value(true)
                 .default_value("extracted_variant.csv"))
+            .arg(Arg::with_name("output_format")
+                .long("output-format")
+                .help("Output format.")
+                .takes_value(true)
+                .possible_values(&["csv", "tsv", "vcf"])
+                .default_value("csv"))
         )
 
         .get_matches();
@@ -460,6 +1175,18 @@ fn main() {
                 matches.subcommand_matches("extract-region").unwrap()
             );
         },
+        Some("correlation") => {
+            cmd_correlation(
+                datasets,
+                matches.subcommand_matches("correlation").unwrap()
+            );
+        },
+        Some("meta-analyze") => {
+            cmd_meta_analyze(
+                datasets,
+                matches.subcommand_matches("meta-analyze").unwrap()
+            );
+        },
         Some(cmd) => println!("Command '{}' isn't supported yet.", cmd),
         None => panic!("No subcommand provided")
     }